@@ -0,0 +1,231 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CAGradientLayer`.
+
+use super::ca_layer::{CALayerHostObject, GradientType};
+use crate::frameworks::core_animation::composition::gradient_color_at;
+use crate::frameworks::core_graphics::cg_bitmap_context::{
+    CGBitmapContextCreate, CGBitmapContextCreateImage, CGBitmapContextGetData,
+};
+use crate::frameworks::core_graphics::cg_color::CGColorGetComponents;
+use crate::frameworks::core_graphics::cg_color_space::CGColorSpaceCreateDeviceRGB;
+use crate::frameworks::core_graphics::cg_context::CGContextRelease;
+use crate::frameworks::core_graphics::cg_image::{
+    kCGImageAlphaPremultipliedLast, kCGImageByteOrder32Big,
+};
+use crate::frameworks::core_graphics::CGPoint;
+use crate::frameworks::foundation::ns_string::to_rust_string;
+use crate::mem::GuestUSize;
+use crate::objc::{id, msg, nil, objc_classes, release, ClassExports};
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation CAGradientLayer: CALayer
+
+- (id)colors {
+    env.objc.borrow::<CALayerHostObject>(this).gradient_colors
+}
+- (())setColors:(id)colors {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let old_colors = std::mem::replace(&mut host_obj.gradient_colors, colors);
+    host_obj.gles_texture_is_up_to_date = false;
+    host_obj.needs_display = true;
+    if colors != nil {
+        () = msg![env; colors retain];
+    }
+    if old_colors != nil {
+        release(env, old_colors);
+    }
+}
+
+- (id)locations {
+    env.objc.borrow::<CALayerHostObject>(this).gradient_locations
+}
+- (())setLocations:(id)locations {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let old_locations = std::mem::replace(&mut host_obj.gradient_locations, locations);
+    host_obj.gles_texture_is_up_to_date = false;
+    host_obj.needs_display = true;
+    if locations != nil {
+        () = msg![env; locations retain];
+    }
+    if old_locations != nil {
+        release(env, old_locations);
+    }
+}
+
+- (CGPoint)startPoint {
+    env.objc.borrow::<CALayerHostObject>(this).gradient_start_point
+}
+- (())setStartPoint:(CGPoint)start_point {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.gradient_start_point = start_point;
+    host_obj.gles_texture_is_up_to_date = false;
+    host_obj.needs_display = true;
+}
+
+- (CGPoint)endPoint {
+    env.objc.borrow::<CALayerHostObject>(this).gradient_end_point
+}
+- (())setEndPoint:(CGPoint)end_point {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.gradient_end_point = end_point;
+    host_obj.gles_texture_is_up_to_date = false;
+    host_obj.needs_display = true;
+}
+
+// NSString*, either kCAGradientLayerAxial (the default) or
+// kCAGradientLayerRadial.
+- (id)type {
+    let gradient_type = env.objc.borrow::<CALayerHostObject>(this).gradient_type;
+    let string = match gradient_type {
+        GradientType::Axial => "axial",
+        GradientType::Radial => "radial",
+    };
+    crate::frameworks::foundation::ns_string::from_rust_string(env, string.to_string())
+}
+- (())setType:(id)type_name {
+    let gradient_type = if to_rust_string(env, type_name) == "radial" {
+        GradientType::Radial
+    } else {
+        GradientType::Axial
+    };
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.gradient_type = gradient_type;
+    host_obj.gles_texture_is_up_to_date = false;
+    host_obj.needs_display = true;
+}
+
+// CAGradientLayer draws itself: unlike a plain CALayer it doesn't need
+// (and mostly ignores) a delegate.
+- (())displayIfNeeded {
+    let &mut CALayerHostObject {
+        ref mut needs_display,
+        bounds,
+        gradient_colors,
+        gradient_start_point,
+        gradient_end_point,
+        gradient_type,
+        opacity,
+        ..
+    } = env.objc.borrow_mut(this);
+    if !std::mem::take(needs_display) {
+        return;
+    }
+    env.objc.borrow_mut::<CALayerHostObject>(this).gles_texture_is_up_to_date = false;
+
+    if gradient_colors == nil {
+        return;
+    }
+    let stop_count: crate::objc::NSUInteger = msg![env; gradient_colors count];
+    if stop_count == 0 {
+        return;
+    }
+
+    let locations = env.objc.borrow::<CALayerHostObject>(this).gradient_locations;
+    // A guest can set `colors` and `locations` to arrays of different
+    // lengths by mistake; fall back to even spacing rather than indexing
+    // `locations` out of bounds in that case.
+    let locations = if locations != nil {
+        let location_count: crate::objc::NSUInteger = msg![env; locations count];
+        (location_count == stop_count).then_some(locations)
+    } else {
+        None
+    };
+    let mut stops: Vec<(f32, [f32; 4])> = Vec::with_capacity(stop_count as usize);
+    for i in 0..stop_count {
+        let color: id = msg![env; gradient_colors objectAtIndex:i];
+        let components = CGColorGetComponents(env, color);
+        let t = if let Some(locations) = locations {
+            let number: id = msg![env; locations objectAtIndex:i];
+            let value: f32 = msg![env; number floatValue];
+            value
+        } else if stop_count == 1 {
+            0.0
+        } else {
+            i as f32 / (stop_count - 1) as f32
+        };
+        // `gradient_color_at` interpolates premultiplied colors, while
+        // `CGColorGetComponents` gives straight ones.
+        let [r, g, b, a] = components;
+        stops.push((t, [r * a, g * a, b * a, a]));
+    }
+
+    let int_width = bounds.size.width.round().max(1.0) as GuestUSize;
+    let int_height = bounds.size.height.round().max(1.0) as GuestUSize;
+
+    let color_space = CGColorSpaceCreateDeviceRGB(env);
+    let cg_context = CGBitmapContextCreate(
+        env,
+        crate::mem::Ptr::null(),
+        int_width,
+        int_height,
+        8, // bpp
+        int_width.checked_mul(4).unwrap(),
+        color_space,
+        kCGImageByteOrder32Big | kCGImageAlphaPremultipliedLast,
+    );
+    let pixels = CGBitmapContextGetData(env, cg_context);
+    let pixels = env.mem.bytes_at_mut(pixels.cast(), int_width.checked_mul(int_height).unwrap().checked_mul(4).unwrap());
+
+    let axis = CGPoint {
+        x: gradient_end_point.x - gradient_start_point.x,
+        y: gradient_end_point.y - gradient_start_point.y,
+    };
+    let axis_len_sq = (axis.x * axis.x + axis.y * axis.y).max(f32::EPSILON);
+
+    for py in 0..int_height {
+        for px in 0..int_width {
+            // Normalized layer-space coordinates, matching how startPoint/
+            // endPoint are specified (unit square over the layer's bounds).
+            let nx = (px as f32 + 0.5) / int_width as f32;
+            let ny = (py as f32 + 0.5) / int_height as f32;
+
+            let t = match gradient_type {
+                GradientType::Axial => {
+                    let dx = nx - gradient_start_point.x;
+                    let dy = ny - gradient_start_point.y;
+                    (dx * axis.x + dy * axis.y) / axis_len_sq
+                }
+                GradientType::Radial => {
+                    let dx = nx - gradient_start_point.x;
+                    let dy = ny - gradient_start_point.y;
+                    (dx * dx + dy * dy).sqrt() / axis_len_sq.sqrt()
+                }
+            };
+            // Already premultiplied, so `opacity` scales all four channels.
+            let [r, g, b, a] = gradient_color_at(t, &stops);
+
+            let idx = ((py * int_width + px) * 4) as usize;
+            pixels[idx] = (r * opacity * 255.0).round() as u8;
+            pixels[idx + 1] = (g * opacity * 255.0).round() as u8;
+            pixels[idx + 2] = (b * opacity * 255.0).round() as u8;
+            pixels[idx + 3] = (a * opacity * 255.0).round() as u8;
+        }
+    }
+
+    let image: id = CGBitmapContextCreateImage(env, cg_context);
+    () = msg![env; this setContents:image];
+    release(env, image);
+
+    // A CAGradientLayer is almost always used as a sublayer (a gradient
+    // background or button embedded in a view hierarchy). The parent's
+    // sublayer-compositing loop only composites from `cg_context` (it
+    // doesn't yet know how to pull from `contents`), so this must populate
+    // `cg_context` too, not just `contents`, or a gradient layer added via
+    // `addSublayer:` would silently never be drawn onto its parent.
+    let old_cg_context = env.objc.borrow::<CALayerHostObject>(this).cg_context;
+    if let Some(old_cg_context) = old_cg_context {
+        CGContextRelease(env, old_cg_context);
+    }
+    env.objc.borrow_mut::<CALayerHostObject>(this).cg_context = Some(cg_context);
+}
+
+@end
+
+};
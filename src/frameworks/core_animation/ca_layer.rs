@@ -5,10 +5,13 @@
  */
 //! `CALayer`.
 
+use super::composition::{self, ShadowStyle};
 use crate::frameworks::core_foundation::{CFRelease, CFRetain};
 use crate::frameworks::core_graphics::cg_bitmap_context::{
-    CGBitmapContextCreate, CGBitmapContextGetHeight, CGBitmapContextGetWidth,
+    CGBitmapContextCreate, CGBitmapContextGetData, CGBitmapContextGetHeight,
+    CGBitmapContextGetWidth,
 };
+use crate::frameworks::core_graphics::cg_color::CGColorGetComponents;
 use crate::frameworks::core_graphics::cg_color_space::CGColorSpaceCreateDeviceRGB;
 use crate::frameworks::core_graphics::cg_context::{
     CGContextRef, CGContextRelease, CGContextTranslateCTM,
@@ -17,9 +20,21 @@ use crate::frameworks::core_graphics::cg_image::{
     kCGImageAlphaPremultipliedLast, kCGImageByteOrder32Big,
 };
 use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::ns_string::to_rust_string;
 use crate::mem::{GuestUSize, Ptr};
 use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, ObjC};
 
+/// Straight (non-premultiplied) RGBA in `[0, 1]` for a `CGColorRef`, or
+/// opaque black if `color` is nil (matching `CALayer`'s own documented
+/// default for `shadowColor`/`borderColor`).
+fn color_components_or_black(env: &mut crate::Environment, color: id) -> [f32; 4] {
+    if color == nil {
+        [0.0, 0.0, 0.0, 1.0]
+    } else {
+        CGColorGetComponents(env, color)
+    }
+}
+
 pub(super) struct CALayerHostObject {
     /// Possibly nil, usually a UIView. This is a weak reference.
     delegate: id,
@@ -47,6 +62,73 @@ pub(super) struct CALayerHostObject {
     pub(super) gles_texture: Option<crate::gles::gles11_raw::types::GLuint>,
     /// Internal state for compositor
     pub(super) gles_texture_is_up_to_date: bool,
+    /// `CGColorRef`, nil is treated as opaque black. Whether a shadow is
+    /// drawn at all depends on `shadow_opacity`.
+    pub(super) shadow_color: id,
+    pub(super) shadow_opacity: f32,
+    pub(super) shadow_radius: f32,
+    pub(super) shadow_offset: CGSize,
+    /// `CGPathRef`, nil unless set explicitly. When present (whatever its
+    /// shape), the compositor doesn't use the analytic rectangle formula and
+    /// runs a real two-pass separable Gaussian blur instead. The path's own
+    /// geometry is not rasterized: the blurred mask is the layer's bounds
+    /// rectangle.
+    pub(super) shadow_path: id,
+    pub(super) corner_radius: f32,
+    pub(super) masks_to_bounds: bool,
+    pub(super) border_width: f32,
+    /// `CGColorRef`
+    pub(super) border_color: id,
+    /// For CAGradientLayer only: `NSArray<CGColorRef>*`, the gradient's
+    /// stop colors from first to last.
+    pub(super) gradient_colors: id,
+    /// For CAGradientLayer only: `NSArray<NSNumber>*` of stop locations in
+    /// `[0, 1]`, parallel to `gradient_colors`. Nil means the stops are
+    /// evenly spaced.
+    pub(super) gradient_locations: id,
+    /// For CAGradientLayer only
+    pub(super) gradient_start_point: CGPoint,
+    /// For CAGradientLayer only
+    pub(super) gradient_end_point: CGPoint,
+    /// For CAGradientLayer only
+    pub(super) gradient_type: GradientType,
+    pub(super) compositing_filter: BlendMode,
+}
+
+/// For CAGradientLayer only: the shape of the gradient's iso-lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum GradientType {
+    Axial,
+    Radial,
+}
+
+/// The blend mode named by `compositingFilter`, which in real Core Animation
+/// is a Core Image filter name (e.g. `"multiplyBlendMode"`). touchHLE only
+/// implements the handful of separable blend modes apps commonly rely on
+/// for lighting/UI effects; anything else falls back to `Normal` (plain
+/// source-over).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Additive,
+}
+impl BlendMode {
+    fn from_filter_name(name: &str) -> Self {
+        match name {
+            "multiplyBlendMode" => BlendMode::Multiply,
+            "screenBlendMode" => BlendMode::Screen,
+            "overlayBlendMode" => BlendMode::Overlay,
+            "darkenBlendMode" => BlendMode::Darken,
+            "lightenBlendMode" => BlendMode::Lighten,
+            "additionCompositing" | "CIAdditionCompositing" => BlendMode::Additive,
+            _ => BlendMode::Normal,
+        }
+    }
 }
 impl HostObject for CALayerHostObject {}
 
@@ -78,6 +160,21 @@ pub const CLASSES: ClassExports = objc_classes! {
         cg_context: None,
         gles_texture: None,
         gles_texture_is_up_to_date: false,
+        shadow_color: nil,
+        shadow_opacity: 0.0,
+        shadow_radius: 3.0,
+        shadow_offset: CGSize { width: 0.0, height: -3.0 },
+        shadow_path: nil,
+        corner_radius: 0.0,
+        masks_to_bounds: false,
+        border_width: 0.0,
+        border_color: nil, // opaque black, but invisible while border_width is 0
+        gradient_colors: nil,
+        gradient_locations: nil,
+        gradient_start_point: CGPoint { x: 0.5, y: 0.0 },
+        gradient_end_point: CGPoint { x: 0.5, y: 1.0 },
+        gradient_type: GradientType::Axial,
+        compositing_filter: BlendMode::Normal,
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -94,6 +191,11 @@ pub const CLASSES: ClassExports = objc_classes! {
         superlayer,
         background_color,
         cg_context,
+        shadow_color,
+        shadow_path,
+        border_color,
+        gradient_colors,
+        gradient_locations,
         ref mut sublayers,
         ..
     } = env.objc.borrow_mut(this);
@@ -111,6 +213,26 @@ pub const CLASSES: ClassExports = objc_classes! {
         CFRelease(env, background_color);
     }
 
+    if shadow_color != nil {
+        CFRelease(env, shadow_color);
+    }
+
+    if shadow_path != nil {
+        CFRelease(env, shadow_path);
+    }
+
+    if border_color != nil {
+        CFRelease(env, border_color);
+    }
+
+    if gradient_colors != nil {
+        release(env, gradient_colors);
+    }
+
+    if gradient_locations != nil {
+        release(env, gradient_locations);
+    }
+
     if let Some(cg_context) = cg_context {
         CGContextRelease(env, cg_context);
     }
@@ -249,6 +371,134 @@ pub const CLASSES: ClassExports = objc_classes! {
     }
 }
 
+// CGColorRef
+- (id)shadowColor {
+    env.objc.borrow::<CALayerHostObject>(this).shadow_color
+}
+- (())setShadowColor:(id)new_color {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let old_color = std::mem::replace(&mut host_obj.shadow_color, new_color);
+    if new_color != nil {
+        CFRetain(env, new_color); // CFRetain doesn't like nil
+    }
+    if old_color != nil {
+        CFRelease(env, old_color); // CFRelease doesn't like nil
+    }
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+- (f32)shadowOpacity {
+    env.objc.borrow::<CALayerHostObject>(this).shadow_opacity
+}
+- (())setShadowOpacity:(f32)shadow_opacity {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.shadow_opacity = shadow_opacity;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+- (f32)shadowRadius {
+    env.objc.borrow::<CALayerHostObject>(this).shadow_radius
+}
+- (())setShadowRadius:(f32)shadow_radius {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.shadow_radius = shadow_radius;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+- (CGSize)shadowOffset {
+    env.objc.borrow::<CALayerHostObject>(this).shadow_offset
+}
+- (())setShadowOffset:(CGSize)shadow_offset {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.shadow_offset = shadow_offset;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+// CGPathRef
+- (id)shadowPath {
+    env.objc.borrow::<CALayerHostObject>(this).shadow_path
+}
+- (())setShadowPath:(id)new_path {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let old_path = std::mem::replace(&mut host_obj.shadow_path, new_path);
+    if new_path != nil {
+        CFRetain(env, new_path); // CFRetain doesn't like nil
+    }
+    if old_path != nil {
+        CFRelease(env, old_path); // CFRelease doesn't like nil
+    }
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+- (f32)cornerRadius {
+    env.objc.borrow::<CALayerHostObject>(this).corner_radius
+}
+- (())setCornerRadius:(f32)corner_radius {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.corner_radius = corner_radius;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+- (bool)masksToBounds {
+    env.objc.borrow::<CALayerHostObject>(this).masks_to_bounds
+}
+- (())setMasksToBounds:(bool)masks_to_bounds {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.masks_to_bounds = masks_to_bounds;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+- (f32)borderWidth {
+    env.objc.borrow::<CALayerHostObject>(this).border_width
+}
+- (())setBorderWidth:(f32)border_width {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.border_width = border_width;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+// CGColorRef
+- (id)borderColor {
+    env.objc.borrow::<CALayerHostObject>(this).border_color
+}
+- (())setBorderColor:(id)new_color {
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let old_color = std::mem::replace(&mut host_obj.border_color, new_color);
+    if new_color != nil {
+        CFRetain(env, new_color); // CFRetain doesn't like nil
+    }
+    if old_color != nil {
+        CFRelease(env, old_color); // CFRelease doesn't like nil
+    }
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
+// NSString*, a Core Image filter name such as "multiplyBlendMode". Setting
+// nil resets the layer to plain source-over compositing.
+- (id)compositingFilter {
+    let mode = env.objc.borrow::<CALayerHostObject>(this).compositing_filter;
+    let name = match mode {
+        BlendMode::Normal => return nil,
+        BlendMode::Multiply => "multiplyBlendMode",
+        BlendMode::Screen => "screenBlendMode",
+        BlendMode::Overlay => "overlayBlendMode",
+        BlendMode::Darken => "darkenBlendMode",
+        BlendMode::Lighten => "lightenBlendMode",
+        BlendMode::Additive => "additionCompositing",
+    };
+    crate::frameworks::foundation::ns_string::from_rust_string(env, name.to_string())
+}
+- (())setCompositingFilter:(id)filter {
+    let mode = if filter == nil {
+        BlendMode::Normal
+    } else {
+        BlendMode::from_filter_name(&to_rust_string(env, filter))
+    };
+    let host_obj = env.objc.borrow_mut::<CALayerHostObject>(this);
+    host_obj.compositing_filter = mode;
+    host_obj.gles_texture_is_up_to_date = false;
+}
+
 - (bool)needsDisplay {
     env.objc.borrow::<CALayerHostObject>(this).needs_display
 }
@@ -358,6 +608,134 @@ pub const CLASSES: ClassExports = objc_classes! {
     CGContextTranslateCTM(env, cg_context, -origin.x, -origin.y);
     () = msg![env; delegate drawLayer:this inContext:cg_context];
     CGContextTranslateCTM(env, cg_context, origin.x, origin.y);
+
+    // Note: a layer's shadow is NOT drawn here, into its own bitmap. Real
+    // Core Animation draws a layer's shadow into its superlayer's space, so
+    // it can extend past the layer's own bounds and show up against whatever
+    // is behind the layer rather than underneath the layer's own (usually
+    // opaque) content. That happens below, in the sublayer-compositing loop,
+    // immediately before each sublayer's content is composited onto us.
+
+    let &CALayerHostObject {
+        corner_radius,
+        border_width,
+        border_color,
+        ..
+    } = env.objc.borrow(this);
+    if corner_radius > 0.0 || border_width > 0.0 {
+        let border_color = color_components_or_black(env, border_color);
+        let pixels_ptr = CGBitmapContextGetData(env, cg_context);
+        let pixel_count = int_width.checked_mul(int_height).unwrap().checked_mul(4).unwrap();
+        let pixels = env.mem.bytes_at_mut(pixels_ptr.cast(), pixel_count);
+        composition::apply_corner_clip_and_border(
+            pixels,
+            int_width,
+            int_height,
+            corner_radius,
+            border_width,
+            border_color,
+        );
+    }
+
+    // Note: `compositingFilter` is NOT applied here, against this layer's
+    // own content in isolation. Real Core Animation blends a layer against
+    // whatever is actually drawn behind it in the tree, which this layer's
+    // own bitmap doesn't have access to. That happens below instead, in the
+    // sublayer-compositing loop, where a sublayer is blended against its
+    // parent's already-composited pixels (the real backdrop) as it's drawn
+    // onto them.
+
+    // Composite each sublayer's own (already-rendered) bitmap onto ours, in
+    // back-to-front order, clipping descendants to our rounded region when
+    // masksToBounds is set.
+    //
+    // TODO: sublayers whose content comes from `contents` (a still image)
+    // or from a CAEAGLLayer's `presented_pixels` rather than from
+    // drawLayer:inContext: aren't composited here yet.
+    let sublayers = env.objc.borrow::<CALayerHostObject>(this).sublayers.clone();
+    if !sublayers.is_empty() {
+        let &CALayerHostObject {
+            masks_to_bounds,
+            corner_radius,
+            ..
+        } = env.objc.borrow(this);
+        let clip_corner_radius = masks_to_bounds.then_some(corner_radius);
+
+        for sublayer in sublayers {
+            if env.objc.borrow::<CALayerHostObject>(sublayer).hidden {
+                continue;
+            }
+            () = msg![env; sublayer displayIfNeeded];
+
+            let &CALayerHostObject {
+                cg_context: sub_cg_context,
+                opacity: sub_opacity,
+                shadow_color: sub_shadow_color,
+                shadow_opacity: sub_shadow_opacity,
+                shadow_radius: sub_shadow_radius,
+                shadow_offset: sub_shadow_offset,
+                shadow_path: sub_shadow_path,
+                compositing_filter: sub_compositing_filter,
+                ..
+            } = env.objc.borrow(sublayer);
+            let Some(sub_cg_context) = sub_cg_context else {
+                continue;
+            };
+            let sub_frame: CGRect = msg![env; sublayer frame];
+            let sub_width = CGBitmapContextGetWidth(env, sub_cg_context);
+            let sub_height = CGBitmapContextGetHeight(env, sub_cg_context);
+            let sub_origin_x = sub_frame.origin.x.round() as i32;
+            let sub_origin_y = sub_frame.origin.y.round() as i32;
+
+            if sub_shadow_opacity > 0.0 {
+                let sub_shadow_color = color_components_or_black(env, sub_shadow_color);
+                let style = ShadowStyle {
+                    color: sub_shadow_color,
+                    opacity: sub_shadow_opacity,
+                    radius: sub_shadow_radius,
+                    offset: sub_shadow_offset,
+                    is_nonrect_path: sub_shadow_path != nil,
+                };
+                let pixels_ptr = CGBitmapContextGetData(env, cg_context);
+                let pixel_count = int_width.checked_mul(int_height).unwrap().checked_mul(4).unwrap();
+                let parent_pixels = env.mem.bytes_at_mut(pixels_ptr.cast(), pixel_count);
+                composition::draw_shadow_into_parent(
+                    parent_pixels,
+                    int_width,
+                    int_height,
+                    sub_origin_x,
+                    sub_origin_y,
+                    sub_width,
+                    sub_height,
+                    &style,
+                );
+            }
+
+            let child_pixels: Vec<u8> = {
+                let ptr = CGBitmapContextGetData(env, sub_cg_context);
+                let count = sub_width.checked_mul(sub_height).unwrap().checked_mul(4).unwrap();
+                env.mem.bytes_at(ptr.cast(), count).to_vec()
+            };
+
+            let pixels_ptr = CGBitmapContextGetData(env, cg_context);
+            let pixel_count = int_width.checked_mul(int_height).unwrap().checked_mul(4).unwrap();
+            let parent_pixels = env.mem.bytes_at_mut(pixels_ptr.cast(), pixel_count);
+
+            composition::composite_sublayer(
+                parent_pixels,
+                int_width,
+                int_height,
+                &child_pixels,
+                sub_width,
+                sub_height,
+                sub_origin_x,
+                sub_origin_y,
+                sub_opacity,
+                sub_compositing_filter,
+                clip_corner_radius,
+            );
+        }
+    }
 }
 
 // CGImageRef*
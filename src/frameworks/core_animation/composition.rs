@@ -0,0 +1,485 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Software compositing helpers shared by the layer tree's display path.
+//!
+//! These are pure math/pixel routines with no `Environment` dependency, so
+//! they can be unit-free-function-tested against the layer tree's state
+//! without dragging in the GLES or Objective-C machinery.
+
+use super::ca_layer::BlendMode;
+use crate::frameworks::core_graphics::CGSize;
+
+/// Approximation of the Gauss error function, accurate to about `1.5e-7`
+/// (Abramowitz & Stegun 7.1.26). Good enough for shadow antialiasing, where
+/// the result only ever feeds an 8-bit alpha channel.
+fn erf(x: f32) -> f32 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+
+    const A1: f32 = 0.254829592;
+    const A2: f32 = -0.284496736;
+    const A3: f32 = 1.421413741;
+    const A4: f32 = -1.453152027;
+    const A5: f32 = 1.061405429;
+    const P: f32 = 0.3275911;
+
+    let t = 1.0 / (1.0 + P * x);
+    let y = 1.0 - (((((A5 * t + A4) * t) + A3) * t + A2) * t + A1) * t * std::f32::consts::E.powf(-x * x);
+    sign * y
+}
+
+/// Coverage, in `[0, 1]`, of a Gaussian blur of sigma `sigma` applied to the
+/// axis-aligned rectangle `[x0, x1] x [y0, y1]`, sampled at `(x, y)`.
+///
+/// The 2-D Gaussian blur of a rectangle factors into the product of two
+/// independent 1-D integrals (one per axis), each of which is an error
+/// function. This lets us evaluate the blurred rectangle analytically
+/// instead of running a real blur pass.
+pub(super) fn box_shadow_coverage(
+    x: f32,
+    y: f32,
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    sigma: f32,
+) -> f32 {
+    if sigma <= 0.0 {
+        let inside = x >= x0 && x < x1 && y >= y0 && y < y1;
+        return if inside { 1.0 } else { 0.0 };
+    }
+    let scale = 1.0 / (std::f32::consts::SQRT_2 * sigma);
+    let cov_x = erf((x1 - x) * scale) - erf((x0 - x) * scale);
+    let cov_y = erf((y1 - y) * scale) - erf((y0 - y) * scale);
+    0.25 * cov_x * cov_y
+}
+
+/// Signed distance from a point `p` (relative to the rectangle's center) to
+/// the boundary of a rounded rectangle of half-extents `half_extents` and
+/// corner radius `radius`. Negative inside, positive outside, zero on the
+/// boundary; the magnitude is the distance in the same units as `p`.
+///
+/// `radius` is clamped to the smaller half-extent so degenerate inputs (a
+/// radius larger than the layer itself) don't produce nonsensical shapes.
+pub(super) fn rounded_rect_sdf(px: f32, py: f32, half_extents: CGSize, radius: f32) -> f32 {
+    let radius = radius.max(0.0).min(half_extents.width.min(half_extents.height));
+    let qx = px.abs() - (half_extents.width - radius);
+    let qy = py.abs() - (half_extents.height - radius);
+    let len = (qx.max(0.0).powi(2) + qy.max(0.0).powi(2)).sqrt();
+    len + qx.max(qy).min(0.0) - radius
+}
+
+/// Antialiasing coverage, in `[0, 1]`, for a pixel at signed distance `sd`
+/// from a shape's boundary (negative is inside). Applies a 1px smoothstep
+/// centered on the boundary.
+pub(super) fn sdf_coverage(sd: f32) -> f32 {
+    let t = (0.5 - sd).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Everything `draw_shadow_into_parent` needs to know about a layer's
+/// shadow, already resolved out of its `CGColorRef`/`CGPathRef` host-object
+/// fields.
+pub(super) struct ShadowStyle {
+    /// Straight (non-premultiplied) RGBA in `[0, 1]`, from `shadowColor`.
+    pub color: [f32; 4],
+    pub opacity: f32,
+    pub radius: f32,
+    pub offset: CGSize,
+    /// Whether `shadowPath` is set. Any non-nil path counts, a plain
+    /// rectangle included, since the path's geometry isn't inspected. When
+    /// true, the analytic box formula isn't used and a real blur of the
+    /// layer's bounds rectangle is used instead.
+    pub is_nonrect_path: bool,
+}
+
+/// Draws a layer's shadow onto its *superlayer's* already-composited canvas
+/// (`parent`, premultiplied RGBA8, row-major, `parent_width * parent_height *
+/// 4` bytes), underneath where that layer's own content will be composited
+/// next. `(child_x, child_y)` is the layer's origin in the parent's pixel
+/// space and `child_width`/`child_height` are its own bitmap's size; this is
+/// exactly the same geometry `composite_sublayer` uses to place the layer's
+/// content, and callers are expected to call this immediately before that.
+///
+/// Drawing into the parent's (larger) canvas, rather than the layer's own
+/// bitmap, is what lets the shadow extend past the layer's bounds (a wide
+/// `shadowRadius` or large `shadowOffset`) and show up against whatever's
+/// behind the layer instead of disappearing under the layer's own opaque
+/// content.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn draw_shadow_into_parent(
+    parent: &mut [u8],
+    parent_width: u32,
+    parent_height: u32,
+    child_x: i32,
+    child_y: i32,
+    child_width: u32,
+    child_height: u32,
+    style: &ShadowStyle,
+) {
+    if style.opacity <= 0.0 || style.color[3] <= 0.0 {
+        return;
+    }
+    let sigma = (style.radius / 2.0).max(0.0);
+    let offset_x = style.offset.width.round() as i32;
+    let offset_y = style.offset.height.round() as i32;
+
+    // The rectangle (in parent space) that the unblurred shadow would cover,
+    // were there no blur at all.
+    let rect_x0 = (child_x + offset_x) as f32;
+    let rect_y0 = (child_y + offset_y) as f32;
+    let rect_x1 = rect_x0 + child_width as f32;
+    let rect_y1 = rect_y0 + child_height as f32;
+
+    // The blur can spread the shadow well past that rectangle; only touch
+    // the padded region it can actually affect, clamped to the parent canvas,
+    // rather than the whole parent (which is usually much bigger than one
+    // sublayer's shadow).
+    let pad = (sigma * 3.0).ceil() as i32 + 1;
+    let x0 = (rect_x0 as i32 - pad).max(0) as u32;
+    let y0 = (rect_y0 as i32 - pad).max(0) as u32;
+    let x1 = ((rect_x1 as i32 + pad).max(0) as u32).min(parent_width);
+    let y1 = ((rect_y1 as i32 + pad).max(0) as u32).min(parent_height);
+    if x0 >= x1 || y0 >= y1 {
+        return;
+    }
+
+    // `shadowPath` being a non-rectangular shape means the analytic formula
+    // (which is only valid for axis-aligned rectangles) can't be used.
+    // touchHLE doesn't rasterize arbitrary `CGPathRef` geometry yet, so this
+    // approximates the path's mask as the layer's own bounds and runs it
+    // through a real separable blur, rather than silently falling back to
+    // the (wrong, for non-rect paths) analytic box formula.
+    // TODO: rasterize the actual `shadowPath` geometry instead of
+    // approximating it as the layer's bounds rectangle.
+    let mw = (x1 - x0) as usize;
+    let mh = (y1 - y0) as usize;
+    let blurred_mask = if style.is_nonrect_path {
+        let mut mask = vec![0.0f32; mw * mh];
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let fx = px as f32 + 0.5;
+                let fy = py as f32 + 0.5;
+                if fx >= rect_x0 && fx < rect_x1 && fy >= rect_y0 && fy < rect_y1 {
+                    mask[(py - y0) as usize * mw + (px - x0) as usize] = 1.0;
+                }
+            }
+        }
+        separable_gaussian_blur(&mut mask, mw, mh, sigma.max(0.001));
+        Some(mask)
+    } else {
+        None
+    };
+
+    for py in y0..y1 {
+        for px in x0..x1 {
+            let coverage = match &blurred_mask {
+                Some(mask) => mask[(py - y0) as usize * mw + (px - x0) as usize],
+                None => box_shadow_coverage(
+                    px as f32 + 0.5,
+                    py as f32 + 0.5,
+                    rect_x0,
+                    rect_y0,
+                    rect_x1,
+                    rect_y1,
+                    sigma,
+                ),
+            };
+            if coverage <= 0.0 {
+                continue;
+            }
+            let shadow_a = style.color[3] * style.opacity * coverage;
+            let idx = ((py * parent_width + px) * 4) as usize;
+            let dst_a = parent[idx + 3] as f32 / 255.0;
+            let out_a = shadow_a + dst_a * (1.0 - shadow_a);
+            if out_a <= 0.0 {
+                continue;
+            }
+            for c in 0..3 {
+                let dst = parent[idx + c] as f32 / 255.0; // premultiplied
+                let shadow = style.color[c] * shadow_a; // premultiply
+                let out = shadow + dst * (1.0 - shadow_a);
+                parent[idx + c] = (out.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+            parent[idx + 3] = (out_a.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+}
+
+/// A real two-pass (horizontal then vertical) Gaussian blur of a coverage
+/// mask, used as the fallback for shadows whose `shadowPath` isn't a plain
+/// rectangle (see `draw_shadow_into_parent`).
+pub(super) fn separable_gaussian_blur(mask: &mut [f32], width: usize, height: usize, sigma: f32) {
+    let radius = ((sigma * 3.0).ceil() as isize).max(1);
+    let mut kernel = Vec::with_capacity((radius * 2 + 1) as usize);
+    let mut sum = 0.0;
+    for i in -radius..=radius {
+        let v = (-((i * i) as f32) / (2.0 * sigma * sigma)).exp();
+        kernel.push(v);
+        sum += v;
+    }
+    for v in kernel.iter_mut() {
+        *v /= sum;
+    }
+
+    let mut tmp = vec![0.0f32; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, i) in (-radius..=radius).enumerate() {
+                let sx = x as isize + i;
+                if sx >= 0 && (sx as usize) < width {
+                    acc += mask[y * width + sx as usize] * kernel[k];
+                }
+            }
+            tmp[y * width + x] = acc;
+        }
+    }
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, i) in (-radius..=radius).enumerate() {
+                let sy = y as isize + i;
+                if sy >= 0 && (sy as usize) < height {
+                    acc += tmp[sy as usize * width + x] * kernel[k];
+                }
+            }
+            mask[y * width + x] = acc;
+        }
+    }
+}
+
+/// Clips `pixels` (premultiplied RGBA8, row-major) to a rounded rectangle of
+/// corner radius `corner_radius` covering the whole canvas, and strokes a
+/// `border_width`-wide border of `border_color` just inside that boundary.
+/// This doesn't depend on `masksToBounds`: a layer can have rounded corners without
+/// `masksToBounds` clipping its descendants, but its own fill is always
+/// clipped to the rounded shape when `corner_radius > 0` (matching real
+/// Core Animation, where `cornerRadius` affects the layer's own background
+/// and border regardless of `masksToBounds`).
+///
+/// Descendant sublayers are clipped to this same rounded region wherever
+/// they're composited onto this layer (when `masksToBounds` is set); that
+/// happens in the code that composites a layer's sublayer tree onto it, not
+/// here, since this function only ever sees one layer's own bitmap.
+pub(super) fn apply_corner_clip_and_border(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    corner_radius: f32,
+    border_width: f32,
+    border_color: [f32; 4],
+) {
+    if corner_radius <= 0.0 && border_width <= 0.0 {
+        return;
+    }
+    let half_extents = CGSize {
+        width: width as f32 / 2.0,
+        height: height as f32 / 2.0,
+    };
+    for y in 0..height {
+        for x in 0..width {
+            let px = (x as f32 + 0.5) - half_extents.width;
+            let py = (y as f32 + 0.5) - half_extents.height;
+            let sd = rounded_rect_sdf(px, py, half_extents, corner_radius);
+            let idx = ((y * width + x) * 4) as usize;
+
+            if corner_radius > 0.0 {
+                let coverage = sdf_coverage(sd);
+                for c in pixels[idx..idx + 4].iter_mut() {
+                    *c = (*c as f32 * coverage).round() as u8;
+                }
+            }
+
+            if border_width > 0.0 {
+                let stroke_coverage = (sdf_coverage(sd) - sdf_coverage(sd + border_width)).clamp(0.0, 1.0);
+                let a = border_color[3] * stroke_coverage;
+                if a <= 0.0 {
+                    continue;
+                }
+                for c in 0..3 {
+                    let existing = pixels[idx + c] as f32 / 255.0;
+                    let stroke = border_color[c] * a; // premultiply
+                    let out = stroke + existing * (1.0 - a);
+                    pixels[idx + c] = (out.clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+                let existing_a = pixels[idx + 3] as f32 / 255.0;
+                let out_a = a + existing_a * (1.0 - a);
+                pixels[idx + 3] = (out_a.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+/// Composites a sublayer's already-rendered bitmap (`child`, premultiplied
+/// RGBA8) onto its parent's bitmap (`parent`, same format), at integer
+/// offset `(offset_x, offset_y)` in the parent's pixel space, modulated by
+/// the sublayer's `opacity`.
+///
+/// When `clip_corner_radius` is `Some`, every destination pixel is also
+/// required to fall inside the *parent's* rounded-rectangle region (the
+/// parent's `masksToBounds` is on): this is what makes `masksToBounds`
+/// intersect each descendant's contribution with the rounded region during
+/// composition, rather than merely rounding the parent's own fill.
+///
+/// `compositing_filter` is the sublayer's own `compositingFilter`. Since
+/// `parent` already holds everything drawn before this sublayer (the
+/// parent's own content, its rounded clip/border, and any earlier siblings),
+/// it's the real, already-composited backdrop the sublayer should blend
+/// against — not an approximation.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn composite_sublayer(
+    parent: &mut [u8],
+    parent_width: u32,
+    parent_height: u32,
+    child: &[u8],
+    child_width: u32,
+    child_height: u32,
+    offset_x: i32,
+    offset_y: i32,
+    opacity: f32,
+    compositing_filter: BlendMode,
+    clip_corner_radius: Option<f32>,
+) {
+    if opacity <= 0.0 {
+        return;
+    }
+    let parent_half_extents = CGSize {
+        width: parent_width as f32 / 2.0,
+        height: parent_height as f32 / 2.0,
+    };
+    for cy in 0..child_height {
+        let py = offset_y + cy as i32;
+        if py < 0 || py >= parent_height as i32 {
+            continue;
+        }
+        for cx in 0..child_width {
+            let px = offset_x + cx as i32;
+            if px < 0 || px >= parent_width as i32 {
+                continue;
+            }
+
+            let mut coverage = opacity;
+            if let Some(radius) = clip_corner_radius {
+                let sd = rounded_rect_sdf(
+                    px as f32 + 0.5 - parent_half_extents.width,
+                    py as f32 + 0.5 - parent_half_extents.height,
+                    parent_half_extents,
+                    radius,
+                );
+                coverage *= sdf_coverage(sd);
+                if coverage <= 0.0 {
+                    continue;
+                }
+            }
+
+            let src_idx = ((cy * child_width + cx) * 4) as usize;
+            let dst_idx = ((py as u32 * parent_width + px as u32) * 4) as usize;
+            let src = [
+                child[src_idx] as f32 / 255.0,
+                child[src_idx + 1] as f32 / 255.0,
+                child[src_idx + 2] as f32 / 255.0,
+                child[src_idx + 3] as f32 / 255.0,
+            ];
+            let dst = [
+                parent[dst_idx] as f32 / 255.0,
+                parent[dst_idx + 1] as f32 / 255.0,
+                parent[dst_idx + 2] as f32 / 255.0,
+                parent[dst_idx + 3] as f32 / 255.0,
+            ];
+            let blended = blend_pixel(compositing_filter, src, dst, coverage);
+            let out_a = blended[3] + dst[3] * (1.0 - blended[3]);
+            for c in 0..3 {
+                let out = blended[c] + dst[c] * (1.0 - blended[3]);
+                parent[dst_idx + c] = (out.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+            parent[dst_idx + 3] = (out_a.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+}
+
+/// Linearly interpolates a premultiplied RGBA color at position `t` along a
+/// list of `(location, color)` stops, clamping `t` to the first/last stop.
+/// `stops` is assumed to already be sorted by location; CAGradientLayer
+/// itself doesn't require `locations` to be sorted, but real gradients
+/// always are in practice and an unsorted list has undefined behavior in
+/// Core Animation too.
+pub(super) fn gradient_color_at(t: f32, stops: &[(f32, [f32; 4])]) -> [f32; 4] {
+    let (first_t, first_color) = *stops.first().unwrap();
+    if t <= first_t {
+        return first_color;
+    }
+    let (last_t, last_color) = *stops.last().unwrap();
+    if t >= last_t {
+        return last_color;
+    }
+    for pair in stops.windows(2) {
+        let (t0, c0) = pair[0];
+        let (t1, c1) = pair[1];
+        if t >= t0 && t <= t1 {
+            let span = (t1 - t0).max(f32::EPSILON);
+            let f = (t - t0) / span;
+            return [
+                c0[0] + (c1[0] - c0[0]) * f,
+                c0[1] + (c1[1] - c0[1]) * f,
+                c0[2] + (c1[2] - c0[2]) * f,
+                c0[3] + (c1[3] - c0[3]) * f,
+            ];
+        }
+    }
+    last_color
+}
+
+/// One channel of a separable blend mode, `a` being the layer's (source)
+/// channel and `b` being the backdrop's, both already in `[0, 1]`.
+fn blend_channel(mode: BlendMode, a: f32, b: f32) -> f32 {
+    match mode {
+        BlendMode::Normal => a,
+        BlendMode::Multiply => a * b,
+        BlendMode::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+        BlendMode::Overlay => {
+            if b < 0.5 {
+                2.0 * a * b
+            } else {
+                1.0 - 2.0 * (1.0 - a) * (1.0 - b)
+            }
+        }
+        BlendMode::Darken => a.min(b),
+        BlendMode::Lighten => a.max(b),
+        BlendMode::Additive => (a + b).min(1.0),
+    }
+}
+
+/// Blends a layer's premultiplied source pixel `src` against the already-
+/// composited `backdrop` pixel beneath it (both premultiplied RGBA in
+/// `[0, 1]`), using `mode` and modulating the result by the layer's
+/// `opacity`. Blend modes other than `Normal` require the compositor to
+/// have already rendered the backdrop, since the blend functions read both
+/// operands.
+pub(super) fn blend_pixel(mode: BlendMode, src: [f32; 4], backdrop: [f32; 4], opacity: f32) -> [f32; 4] {
+    let blended = if mode == BlendMode::Normal {
+        src
+    } else {
+        // Un-premultiply before blending channel-wise, then re-premultiply,
+        // since the blend formulas are defined on straight alpha colors.
+        let src_a = src[3].max(f32::EPSILON);
+        let backdrop_a = backdrop[3].max(f32::EPSILON);
+        let mut out = [0.0; 4];
+        for i in 0..3 {
+            let sc = src[i] / src_a;
+            let bc = backdrop[i] / backdrop_a;
+            out[i] = blend_channel(mode, sc, bc) * src[3];
+        }
+        out[3] = src[3];
+        out
+    };
+    [
+        blended[0] * opacity,
+        blended[1] * opacity,
+        blended[2] * opacity,
+        blended[3] * opacity,
+    ]
+}
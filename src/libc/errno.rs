@@ -0,0 +1,57 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `errno.h`
+//!
+//! On Apple platforms `errno` is a macro expanding to `*__error()`, a
+//! per-thread cell. touchHLE doesn't yet model per-thread `errno`: there is
+//! a single guest-memory cell shared by the whole process, lazily allocated
+//! and handed out from `__error`. This is fine for the common case of a
+//! single guest thread doing I/O, but concurrent guest threads would stomp
+//! each other's `errno`.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::MutPtr;
+use crate::Environment;
+
+pub type Errno = i32;
+
+pub const EPERM: Errno = 1;
+pub const ENOENT: Errno = 2;
+pub const EBADF: Errno = 9;
+pub const EEXIST: Errno = 17;
+pub const ENOTDIR: Errno = 20;
+pub const EISDIR: Errno = 21;
+pub const EINVAL: Errno = 22;
+pub const ENOSPC: Errno = 28;
+
+#[derive(Default)]
+pub struct State {
+    /// Lazily-allocated guest cell backing `__error()`'s return value.
+    /// Shared by the whole process; see the module doc comment.
+    cell: Option<MutPtr<Errno>>,
+}
+
+fn cell(env: &mut Environment) -> MutPtr<Errno> {
+    if let Some(cell) = env.libc_state.errno.cell {
+        return cell;
+    }
+    let cell = env.mem.alloc_and_write(0);
+    env.libc_state.errno.cell = Some(cell);
+    cell
+}
+
+/// Sets the guest-visible `errno` to `value`. Call this from any libc
+/// function whose POSIX contract says it sets `errno` on failure.
+pub fn set_errno(env: &mut Environment, value: Errno) {
+    let cell = cell(env);
+    env.mem.write(cell, value);
+}
+
+fn __error(env: &mut Environment) -> MutPtr<Errno> {
+    cell(env)
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(__error())];
@@ -8,9 +8,10 @@
 use crate::abi::VAList;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::{GuestFile, GuestOpenOptions, GuestPath};
-use crate::mem::{ConstPtr, GuestISize, GuestUSize, MutVoidPtr};
+use crate::libc::errno::{set_errno, EBADF, EINVAL, ENOENT};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, MutVoidPtr};
 use crate::Environment;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom, Write};
 
 #[derive(Default)]
 pub struct State {
@@ -19,16 +20,72 @@ pub struct State {
 }
 impl State {
     fn file_for_fd(&mut self, fd: FileDescriptor) -> Option<&mut PosixFileHostObject> {
-        self.files
-            .get_mut(fd_to_file_idx(fd))
+        fd_to_file_idx(fd)
+            .and_then(|idx| self.files.get_mut(idx))
             .and_then(|file_or_none| file_or_none.as_mut())
     }
+
+    /// Inserts `host_object` into the first free slot (reusing one left
+    /// behind by `close`, if any) and returns the resulting file descriptor.
+    fn insert_file(&mut self, host_object: PosixFileHostObject) -> FileDescriptor {
+        let idx = if let Some(free_idx) = self.files.iter().position(|f| f.is_none()) {
+            self.files[free_idx] = Some(host_object);
+            free_idx
+        } else {
+            let idx = self.files.len();
+            self.files.push(Some(host_object));
+            idx
+        };
+        file_idx_to_fd(idx)
+    }
 }
 
 struct PosixFileHostObject {
     file: GuestFile,
 }
 
+/// `off_t`. Like the real platform, this is 32-bit.
+#[allow(non_camel_case_types)]
+type off_t = GuestISize;
+
+type Whence = i32;
+const SEEK_SET: Whence = 0;
+const SEEK_CUR: Whence = 1;
+const SEEK_END: Whence = 2;
+
+/// Mirrors the layout of the guest `struct stat`. `fstat` only fills in the
+/// fields apps actually look at (size, mode, inode, link count, block size
+/// and count); the others (timestamps, device numbers, etc) aren't modeled
+/// and are zeroed.
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct stat {
+    st_dev: i32,
+    st_ino: u32,
+    st_mode: u16,
+    st_nlink: u16,
+    st_uid: u32,
+    st_gid: u32,
+    st_rdev: i32,
+    // `st_size` is an `off_t` field, so it must agree with `off_t`'s width
+    // above, not just be "some 64-bit integer".
+    st_size: off_t,
+    st_atime: i32,
+    st_atimensec: i32,
+    st_mtime: i32,
+    st_mtimensec: i32,
+    st_ctime: i32,
+    st_ctimensec: i32,
+    st_blksize: i32,
+    st_blocks: i64,
+    st_flags: u32,
+    st_gen: u32,
+}
+unsafe impl crate::mem::SafeRead for stat {}
+
+/// A plain regular file, `0o100644` (`S_IFREG | 0644`).
+const S_IFREG_0644: u16 = 0o100644;
+
 // TODO: stdin/stdout/stderr handling somehow
 fn file_idx_to_fd(idx: usize) -> FileDescriptor {
     FileDescriptor::try_from(idx)
@@ -36,8 +93,14 @@ fn file_idx_to_fd(idx: usize) -> FileDescriptor {
         .checked_add(NORMAL_FILENO_BASE)
         .unwrap()
 }
-fn fd_to_file_idx(fd: FileDescriptor) -> usize {
-    fd.checked_sub(NORMAL_FILENO_BASE).unwrap() as usize
+/// Returns `None` for `fd`s that can never correspond to a table entry:
+/// negative fds and the reserved stdin/stdout/stderr fds below
+/// `NORMAL_FILENO_BASE`. Guest code calling e.g. `write(STDOUT_FILENO, ...)`
+/// is completely ordinary, so this must fail gracefully rather than panic.
+fn fd_to_file_idx(fd: FileDescriptor) -> Option<usize> {
+    fd.checked_sub(NORMAL_FILENO_BASE)
+        .filter(|&idx| idx >= 0)
+        .map(|idx| idx as usize)
 }
 
 /// File descriptor type. This alias is for readability, POSIX just uses `int`.
@@ -101,27 +164,16 @@ fn open(env: &mut Environment, path: ConstPtr<u8>, flags: i32, _args: VAList) ->
     ) {
         Ok(file) => {
             let host_object = PosixFileHostObject { file };
-
-            let idx = if let Some(free_idx) = env
-                .libc_state
-                .posix_io
-                .files
-                .iter()
-                .position(|f| f.is_none())
-            {
-                env.libc_state.posix_io.files[free_idx] = Some(host_object);
-                free_idx
-            } else {
-                let idx = env.libc_state.posix_io.files.len();
-                env.libc_state.posix_io.files.push(Some(host_object));
-                idx
-            };
-            let fd = file_idx_to_fd(idx);
+            let fd = env.libc_state.posix_io.insert_file(host_object);
             log_dbg!("open({:?}, {:#x}) => {:?}", path, flags, fd);
             fd
         }
         Err(()) => {
-            // TODO: set errno
+            // The real open() distinguishes many causes here (ENOENT,
+            // EACCES, ...); touchHLE's fs layer doesn't currently expose
+            // enough detail to tell them apart, so ENOENT is the closest
+            // approximation for the common case (path doesn't exist).
+            set_errno(env, ENOENT);
             log!(
                 "Warning: open({:?}, {:#x}) failed, returning -1",
                 path,
@@ -138,11 +190,12 @@ fn read(
     buffer: MutVoidPtr,
     size: GuestUSize,
 ) -> GuestISize {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
 
     let buffer_slice = env.mem.bytes_at_mut(buffer.cast(), size);
-    // TODO: handle errors
     match file.file.read(buffer_slice) {
         Ok(bytes_read) => {
             if bytes_read < buffer_slice.len() {
@@ -165,7 +218,7 @@ fn read(
             bytes_read.try_into().unwrap()
         }
         Err(e) => {
-            // TODO: set errno
+            set_errno(env, EINVAL);
             log!(
                 "Warning: read({:?}, {:?}, {:#x}) encountered error {:?}, returning -1",
                 fd,
@@ -178,11 +231,273 @@ fn read(
     }
 }
 
+fn write(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    buffer: ConstVoidPtr,
+    size: GuestUSize,
+) -> GuestISize {
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+
+    let buffer_slice = env.mem.bytes_at(buffer.cast(), size);
+    match file.file.write(buffer_slice) {
+        Ok(bytes_written) => {
+            if bytes_written < buffer_slice.len() {
+                log!(
+                    "Warning: write({:?}, {:?}, {:#x}) wrote only {:#x} bytes",
+                    fd,
+                    buffer,
+                    size,
+                    bytes_written,
+                );
+            } else {
+                log_dbg!(
+                    "write({:?}, {:?}, {:#x}) => {:#x}",
+                    fd,
+                    buffer,
+                    size,
+                    bytes_written,
+                );
+            }
+            bytes_written.try_into().unwrap()
+        }
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!(
+                "Warning: write({:?}, {:?}, {:#x}) encountered error {:?}, returning -1",
+                fd,
+                buffer,
+                size,
+                e,
+            );
+            -1
+        }
+    }
+}
+
+fn lseek(env: &mut Environment, fd: FileDescriptor, offset: off_t, whence: Whence) -> off_t {
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+
+    // POSIX requires EINVAL when the resulting offset would be negative.
+    // Compute the resulting (signed) position up front rather than trusting
+    // `as u64`/`as i64` casts of a possibly-negative `offset`: reinterpreting
+    // a negative `off_t` as a huge unsigned `SeekFrom::Start` would otherwise
+    // ask the underlying file to seek to a position around `u64::MAX`,
+    // which some seek implementations happily accept.
+    let resulting_pos: i64 = match whence {
+        SEEK_SET => offset as i64,
+        SEEK_CUR => match file.file.stream_position() {
+            Ok(current) => current as i64 + offset as i64,
+            Err(e) => {
+                set_errno(env, EINVAL);
+                log!("Warning: lseek({:?}, {:#x}, {:?}) encountered error {:?}, returning -1", fd, offset, whence, e);
+                return -1;
+            }
+        },
+        SEEK_END => match file.file.stream_len() {
+            Ok(len) => len as i64 + offset as i64,
+            Err(e) => {
+                set_errno(env, EINVAL);
+                log!("Warning: lseek({:?}, {:#x}, {:?}) encountered error {:?}, returning -1", fd, offset, whence, e);
+                return -1;
+            }
+        },
+        _ => {
+            set_errno(env, EINVAL);
+            return -1;
+        }
+    };
+    if resulting_pos < 0 {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(offset as u64),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => unreachable!(),
+    };
+
+    match file.file.seek(pos) {
+        Ok(new_offset) => match new_offset.try_into() {
+            Ok(new_offset) => {
+                log_dbg!("lseek({:?}, {:#x}, {:?}) => {:#x}", fd, offset, whence, new_offset);
+                new_offset
+            }
+            Err(_) => {
+                // The new position doesn't fit back into the (32-bit) off_t
+                // we report to the guest; there's no POSIX errno for "file
+                // too large to represent", but returning -1 beats panicking.
+                set_errno(env, EINVAL);
+                log!("Warning: lseek({:?}, {:#x}, {:?}) resulted in an offset too large for off_t, returning -1", fd, offset, whence);
+                -1
+            }
+        },
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!(
+                "Warning: lseek({:?}, {:#x}, {:?}) encountered error {:?}, returning -1",
+                fd,
+                offset,
+                whence,
+                e,
+            );
+            -1
+        }
+    }
+}
+
+fn fstat(env: &mut Environment, fd: FileDescriptor, buf: MutVoidPtr) -> i32 {
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+
+    let size = match file.file.stream_len() {
+        Ok(size) => size,
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!("Warning: fstat({:?}, {:?}) encountered error {:?}, returning -1", fd, buf, e);
+            return -1;
+        }
+    };
+
+    let result = stat {
+        st_dev: 0,
+        // Synthetic: real inodes don't exist in touchHLE's virtual FS, but
+        // some apps merely check it's non-zero and consistent across calls.
+        st_ino: fd_to_file_idx(fd).unwrap() as u32 + 1,
+        st_mode: S_IFREG_0644,
+        st_nlink: 1,
+        st_uid: 0,
+        st_gid: 0,
+        st_rdev: 0,
+        st_size: size as off_t,
+        st_atime: 0,
+        st_atimensec: 0,
+        st_mtime: 0,
+        st_mtimensec: 0,
+        st_ctime: 0,
+        st_ctimensec: 0,
+        st_blksize: 4096,
+        st_blocks: (size as i64 + 511) / 512,
+        st_flags: 0,
+        st_gen: 0,
+    };
+    env.mem.write(buf.cast(), result);
+    log_dbg!("fstat({:?}, {:?}) => 0", fd, buf);
+    0
+}
+
+fn ftruncate(env: &mut Environment, fd: FileDescriptor, length: off_t) -> i32 {
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+
+    // As with lseek, a negative `length` must not be reinterpreted as a huge
+    // unsigned size via `as u64`; POSIX says EINVAL for a negative length.
+    if length < 0 {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+
+    match file.file.set_len(length as u64) {
+        Ok(()) => {
+            log_dbg!("ftruncate({:?}, {:#x}) => 0", fd, length);
+            0
+        }
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!(
+                "Warning: ftruncate({:?}, {:#x}) encountered error {:?}, returning -1",
+                fd,
+                length,
+                e,
+            );
+            -1
+        }
+    }
+}
+
+fn dup(env: &mut Environment, fd: FileDescriptor) -> FileDescriptor {
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+
+    match file.file.try_clone() {
+        Ok(file) => {
+            let new_fd = env
+                .libc_state
+                .posix_io
+                .insert_file(PosixFileHostObject { file });
+            log_dbg!("dup({:?}) => {:?}", fd, new_fd);
+            new_fd
+        }
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!("Warning: dup({:?}) encountered error {:?}, returning -1", fd, e);
+            -1
+        }
+    }
+}
+
+fn dup2(env: &mut Environment, fd: FileDescriptor, new_fd: FileDescriptor) -> FileDescriptor {
+    if fd == new_fd {
+        return if env.libc_state.posix_io.file_for_fd(fd).is_some() {
+            new_fd
+        } else {
+            set_errno(env, EBADF);
+            -1
+        };
+    }
+
+    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+    let cloned = match file.file.try_clone() {
+        Ok(file) => file,
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!("Warning: dup2({:?}, {:?}) encountered error {:?}, returning -1", fd, new_fd, e);
+            return -1;
+        }
+    };
+
+    // `new_fd` is a guest-controlled `i32`; a real process table is bounded
+    // by a resource limit (`RLIMIT_NOFILE`), so reject anything past a
+    // generous fixed ceiling rather than growing the table to match
+    // whatever the guest asked for.
+    const MAX_FD_TABLE_LEN: usize = 4096;
+    let Some(idx) = fd_to_file_idx(new_fd).filter(|&idx| idx < MAX_FD_TABLE_LEN) else {
+        set_errno(env, EBADF);
+        return -1;
+    };
+    if idx >= env.libc_state.posix_io.files.len() {
+        env.libc_state.posix_io.files.resize_with(idx + 1, || None);
+    }
+    env.libc_state.posix_io.files[idx] = Some(PosixFileHostObject { file: cloned });
+    log_dbg!("dup2({:?}, {:?}) => {:?}", fd, new_fd, new_fd);
+    new_fd
+}
+
 fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.files[fd_to_file_idx(fd)]
-        .take()
-        .unwrap();
+    let Some(file) = fd_to_file_idx(fd)
+        .and_then(|idx| env.libc_state.posix_io.files.get_mut(idx))
+        .and_then(Option::take)
+    else {
+        set_errno(env, EBADF);
+        return -1;
+    };
     // The actual closing of the file happens implicitly when `file` falls out
     // of scope. The return value is about whether flushing succeeds.
     match file.file.sync_all() {
@@ -190,9 +505,9 @@ fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
             log_dbg!("close({:?}) => 0", fd);
             0
         }
-        Err(_) => {
-            // TODO: set errno
-            log!("Warning: close({:?}) failed, returning -1", fd);
+        Err(e) => {
+            set_errno(env, EINVAL);
+            log!("Warning: close({:?}) failed with error {:?}, returning -1", fd, e);
             -1
         }
     }
@@ -201,5 +516,11 @@ fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(open(_, _, _)),
     export_c_func!(read(_, _, _)),
+    export_c_func!(write(_, _, _)),
+    export_c_func!(lseek(_, _, _)),
+    export_c_func!(fstat(_, _)),
+    export_c_func!(ftruncate(_, _)),
+    export_c_func!(dup(_)),
+    export_c_func!(dup2(_, _)),
     export_c_func!(close(_)),
 ];